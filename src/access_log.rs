@@ -0,0 +1,123 @@
+use crate::{client_ip::ClientIp, listener::RemoteAddr, request_id::RequestId};
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, Request},
+    http::{HeaderMap, header},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    io::{self, Write},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Writes one line per request to stdout in Apache Combined Log Format,
+/// followed by the time taken to produce the response in microseconds and
+/// the request ID.
+pub async fn log_requests(
+    ConnectInfo(remote): ConnectInfo<RemoteAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = match (request.extensions().get::<ClientIp>(), remote) {
+        (Some(ClientIp(ip)), _) => ip.to_string(),
+        (None, RemoteAddr::Tcp(addr)) => addr.ip().to_string(),
+        (None, RemoteAddr::Unix) => "-".to_string(),
+    };
+    let start = Instant::now();
+    let received = SystemTime::now();
+    let request_line = format!(
+        "{} {} {:?}",
+        request.method(),
+        escape(
+            request
+                .uri()
+                .path_and_query()
+                .map_or("/", |path| path.as_str())
+        ),
+        request.version()
+    );
+    let referer = header_or_dash(request.headers(), header::REFERER);
+    let user_agent = header_or_dash(request.headers(), header::USER_AGENT);
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|RequestId(id)| id.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+
+    let response = next.run(request).await;
+
+    let bytes = response
+        .body()
+        .size_hint()
+        .exact()
+        .map_or_else(|| "-".to_string(), |len| len.to_string());
+    // A closed stdout must not take the request down with it, so write errors
+    // are dropped rather than panicking as `println!` would.
+    let _ = writeln!(
+        io::stdout().lock(),
+        "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\" {} {}",
+        client,
+        format_clf_time(received),
+        request_line,
+        response.status().as_u16(),
+        bytes,
+        referer,
+        user_agent,
+        start.elapsed().as_micros(),
+        request_id
+    );
+    response
+}
+
+fn header_or_dash(headers: &HeaderMap, name: header::HeaderName) -> String {
+    escape(
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("-"),
+    )
+}
+
+/// Escapes backslashes and quotes so a value cannot end its quoted field early.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Formats a timestamp as `10/Oct/2000:13:55:36 +0000`.
+fn format_clf_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+// Howard Hinnant's days-to-civil algorithm for the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
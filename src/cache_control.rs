@@ -0,0 +1,25 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+
+/// Adds `value` as the `Cache-Control` header of successful responses that
+/// did not set one themselves. Errors are left uncached so a page that is
+/// added later is not hidden behind a cached 404.
+pub async fn set_cache_control(
+    State(value): State<HeaderValue>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    let cacheable = matches!(
+        response.status(),
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT | StatusCode::NOT_MODIFIED
+    );
+    if cacheable && !response.headers().contains_key(header::CACHE_CONTROL) {
+        response.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
+    response
+}
@@ -0,0 +1,324 @@
+use crate::listener::RemoteAddr;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+/// An address block in CIDR notation, e.g. `10.0.0.0/8` or `::1/128`. A bare
+/// address is treated as a single-host block.
+#[derive(Clone, Debug)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl IpNet {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                mask(u32::from(net).into(), self.prefix, 32)
+                    == mask(u32::from(ip).into(), self.prefix, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                mask(net.into(), self.prefix, 128) == mask(ip.into(), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn mask(bits: u128, prefix: u32, width: u32) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        bits >> (width - prefix)
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| ())?;
+        let width = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| ())?,
+            None => width,
+        };
+        if prefix > width {
+            return Err(());
+        }
+        Ok(IpNet { addr, prefix })
+    }
+}
+
+/// Which header trusted proxies report the client in. Only the configured one
+/// is read, since a proxy passes the other through untouched and a client could
+/// use it to claim any address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForwardedHeader {
+    /// The standard `Forwarded` header from RFC 7239.
+    Forwarded,
+    XForwardedFor,
+}
+
+impl FromStr for ForwardedHeader {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forwarded" => Ok(ForwardedHeader::Forwarded),
+            "x-forwarded-for" => Ok(ForwardedHeader::XForwardedFor),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The proxies allowed to report the client address, and how they report it.
+#[derive(Clone, Debug)]
+pub struct TrustedProxies {
+    pub nets: Vec<IpNet>,
+    pub header: ForwardedHeader,
+}
+
+/// The originating client's address, after walking the forwarding chain added
+/// by trusted proxies.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+pub async fn resolve_client_ip(
+    State(trusted): State<Arc<TrustedProxies>>,
+    ConnectInfo(remote): ConnectInfo<RemoteAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Some(ip) = client_ip(remote, request.headers(), &trusted) {
+        request.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(request).await
+}
+
+/// Starting from the peer, steps back through the forwarding chain for as long
+/// as the hop we received the request from is trusted. An unparseable entry
+/// stops the walk at the last trusted hop.
+///
+/// Peers on a Unix socket are local processes and always trusted; without a
+/// forwarding header there is no IP to report for them.
+fn client_ip(remote: RemoteAddr, headers: &HeaderMap, trusted: &TrustedProxies) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted.nets.iter().any(|net| net.contains(ip));
+
+    let mut ip = match remote {
+        RemoteAddr::Tcp(addr) if !is_trusted(addr.ip()) => return Some(addr.ip()),
+        RemoteAddr::Tcp(addr) => Some(addr.ip()),
+        RemoteAddr::Unix => None,
+    };
+    for hop in forwarded_chain(headers, trusted.header).into_iter().rev() {
+        let Some(hop) = hop else {
+            break;
+        };
+        ip = Some(hop);
+        if !is_trusted(hop) {
+            break;
+        }
+    }
+    ip
+}
+
+/// Returns the addresses listed by the given header, oldest hop first.
+fn forwarded_chain(headers: &HeaderMap, header: ForwardedHeader) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+    };
+    match header {
+        ForwardedHeader::Forwarded => values("forwarded")
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_node(value))
+            })
+            .collect(),
+        ForwardedHeader::XForwardedFor => values("x-forwarded-for").map(parse_node).collect(),
+    }
+}
+
+/// Parses a node such as `192.0.2.1`, `"192.0.2.1:4711"` or `"[2001:db8::1]"`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn net(s: &str) -> IpNet {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn tcp(s: &str) -> RemoteAddr {
+        RemoteAddr::Tcp(SocketAddr::new(ip(s), 4711))
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn proxies(nets: &[&str], header: ForwardedHeader) -> TrustedProxies {
+        TrustedProxies {
+            nets: nets.iter().map(|s| net(s)).collect(),
+            header,
+        }
+    }
+
+    #[test]
+    fn net_contains_addresses_in_the_block() {
+        assert!(net("10.0.0.0/8").contains(ip("10.1.2.3")));
+        assert!(!net("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(net("192.0.2.1").contains(ip("192.0.2.1")));
+        assert!(!net("192.0.2.1").contains(ip("192.0.2.2")));
+        assert!(net("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(net("2001:db8::/32").contains(ip("2001:db8::1")));
+        assert!(!net("2001:db8::/32").contains(ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn net_matches_mapped_ipv4_but_not_across_families() {
+        assert!(net("127.0.0.0/8").contains(ip("::ffff:127.0.0.1")));
+        assert!(!net("::/0").contains(ip("127.0.0.1")));
+    }
+
+    #[test]
+    fn net_rejects_bad_blocks() {
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("::/129".parse::<IpNet>().is_err());
+        assert!("10.0.0.0/x".parse::<IpNet>().is_err());
+        assert!("example.com".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn parses_nodes() {
+        assert_eq!(parse_node(" 192.0.2.1 "), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("\"192.0.2.1:4711\""), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("\"[2001:db8::1]\""), Some(ip("2001:db8::1")));
+        assert_eq!(
+            parse_node("\"[2001:db8::1]:4711\""),
+            Some(ip("2001:db8::1"))
+        );
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn untrusted_peer_is_the_client() {
+        let trusted = proxies(&["10.0.0.0/8"], ForwardedHeader::XForwardedFor);
+        let headers = headers(&[("x-forwarded-for", "198.51.100.7")]);
+        assert_eq!(
+            client_ip(tcp("203.0.113.9"), &headers, &trusted),
+            Some(ip("203.0.113.9"))
+        );
+    }
+
+    #[test]
+    fn walks_back_through_trusted_proxies() {
+        let trusted = proxies(&["10.0.0.0/8"], ForwardedHeader::XForwardedFor);
+        let headers = headers(&[("x-forwarded-for", "1.1.1.1, 198.51.100.7, 10.0.0.2")]);
+        assert_eq!(
+            client_ip(tcp("10.0.0.1"), &headers, &trusted),
+            Some(ip("198.51.100.7"))
+        );
+    }
+
+    #[test]
+    fn stops_at_an_unparseable_hop() {
+        let trusted = proxies(&["10.0.0.0/8"], ForwardedHeader::XForwardedFor);
+        let headers = headers(&[("x-forwarded-for", "198.51.100.7, garbage, 10.0.0.2")]);
+        assert_eq!(
+            client_ip(tcp("10.0.0.1"), &headers, &trusted),
+            Some(ip("10.0.0.2"))
+        );
+    }
+
+    #[test]
+    fn reads_the_forwarded_header() {
+        let trusted = proxies(&["10.0.0.0/8"], ForwardedHeader::Forwarded);
+        let headers = headers(&[(
+            "forwarded",
+            "for=198.51.100.7;proto=https, For=\"[2001:db8::1]:4711\"",
+        )]);
+        assert_eq!(
+            client_ip(tcp("10.0.0.1"), &headers, &trusted),
+            Some(ip("2001:db8::1"))
+        );
+    }
+
+    #[test]
+    fn ignores_a_client_set_forwarded_header() {
+        // The proxy appends to X-Forwarded-For and passes Forwarded through as
+        // the client sent it.
+        let trusted = proxies(&["10.0.0.0/8"], ForwardedHeader::XForwardedFor);
+        let headers = headers(&[
+            ("forwarded", "for=192.0.2.66"),
+            ("x-forwarded-for", "198.51.100.7"),
+        ]);
+        assert_eq!(
+            client_ip(tcp("10.0.0.1"), &headers, &trusted),
+            Some(ip("198.51.100.7"))
+        );
+    }
+
+    #[test]
+    fn ignores_a_client_set_x_forwarded_for_header() {
+        let trusted = proxies(&["10.0.0.0/8"], ForwardedHeader::Forwarded);
+        let headers = headers(&[
+            ("forwarded", "for=198.51.100.7"),
+            ("x-forwarded-for", "192.0.2.66"),
+        ]);
+        assert_eq!(
+            client_ip(tcp("10.0.0.1"), &headers, &trusted),
+            Some(ip("198.51.100.7"))
+        );
+    }
+
+    #[test]
+    fn unix_peer_uses_the_forwarded_address() {
+        let trusted = proxies(&[], ForwardedHeader::XForwardedFor);
+        let headers = headers(&[("x-forwarded-for", "198.51.100.7")]);
+        assert_eq!(
+            client_ip(RemoteAddr::Unix, &headers, &trusted),
+            Some(ip("198.51.100.7"))
+        );
+    }
+}
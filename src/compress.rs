@@ -0,0 +1,58 @@
+use axum::{
+    extract::Request,
+    http::{Extensions, HeaderMap, HeaderValue, StatusCode, Version, header},
+    middleware::Next,
+    response::Response,
+};
+use tower_http::compression::{
+    CompressionLayer,
+    predicate::{Predicate, SizeAbove},
+};
+
+// Bodies smaller than this gain too little to be worth compressing.
+const MIN_SIZE: u16 = 256;
+
+/// Compresses text responses with Brotli or gzip, whichever the client's
+/// `Accept-Encoding` prefers. Only complete 200 responses with a compressible
+/// content type are touched; ranges, 304s, errors and images pass through as
+/// they are. The body is encoded as it streams, never buffered whole.
+pub fn compression() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(SizeAbove::new(MIN_SIZE).and(is_compressible))
+}
+
+/// Makes a strong ETag weak on a compressed response, since the bytes no
+/// longer match the representation it was computed for. Weak comparison in
+/// `If-None-Match` still matches it against the original tag.
+///
+/// Must sit outside the compression layer so it sees the encoded response.
+pub async fn weaken_etag(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if headers.contains_key(header::CONTENT_ENCODING)
+        && let Some(etag) = headers.get(header::ETAG)
+        && !etag.as_bytes().starts_with(b"W/")
+    {
+        let mut weak = b"W/".to_vec();
+        weak.extend_from_slice(etag.as_bytes());
+        if let Ok(weak) = HeaderValue::from_bytes(&weak) {
+            headers.insert(header::ETAG, weak);
+        }
+    }
+    response
+}
+
+fn is_compressible(status: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let mime = content_type.split(';').next().unwrap_or("").trim();
+    status == StatusCode::OK
+        && (mime.starts_with("text/")
+            || matches!(
+                mime,
+                "application/json" | "application/javascript" | "image/svg+xml"
+            ))
+}
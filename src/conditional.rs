@@ -0,0 +1,308 @@
+use axum::http::{HeaderMap, StatusCode};
+use std::{
+    hash::{DefaultHasher, Hasher},
+    time::SystemTime,
+};
+
+/// Outcome of evaluating a request's preconditions against a representation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Precondition {
+    Proceed,
+    /// Answer 304 Not Modified.
+    NotModified,
+    /// Answer 412 Precondition Failed.
+    Failed,
+}
+
+impl Precondition {
+    /// The status to answer with instead of the representation, if any.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Precondition::Proceed => None,
+            Precondition::NotModified => Some(StatusCode::NOT_MODIFIED),
+            Precondition::Failed => Some(StatusCode::PRECONDITION_FAILED),
+        }
+    }
+}
+
+/// Formats a strong entity tag from a content digest.
+pub fn strong_etag(digest: &[u8]) -> String {
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Formats a weak entity tag for a generated body. Weak because the hash is
+/// only meant to tell versions of the body apart, not to prove byte identity.
+pub fn weak_etag(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body);
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/// Evaluates `If-Match`, `If-Unmodified-Since`, `If-None-Match` and
+/// `If-Modified-Since` in the order RFC 9110 section 13.2.2 prescribes for
+/// GET and HEAD requests.
+pub fn evaluate(
+    headers: &HeaderMap,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+) -> Precondition {
+    if let Some(if_match) = header_str(headers, "if-match") {
+        if !matches_any(if_match, etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let (Some(since), Some(last_modified)) =
+        (header_date(headers, "if-unmodified-since"), last_modified)
+        && last_modified > since
+    {
+        return Precondition::Failed;
+    }
+
+    if let Some(if_none_match) = header_str(headers, "if-none-match") {
+        if matches_any(if_none_match, etag, false) {
+            return Precondition::NotModified;
+        }
+    } else if let (Some(since), Some(last_modified)) =
+        (header_date(headers, "if-modified-since"), last_modified)
+        && last_modified <= since
+    {
+        return Precondition::NotModified;
+    }
+
+    Precondition::Proceed
+}
+
+/// Whether a `Range` header may be honored under `If-Range` (RFC 9110
+/// section 13.1.5). Without `If-Range` it always may; otherwise the validator
+/// must still match, so a client never splices ranges of two different
+/// versions together. Entity tags are compared strongly, and a date must equal
+/// the last modification time exactly.
+pub fn if_range(
+    headers: &HeaderMap,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    let Some(if_range) = header_str(headers, "if-range") else {
+        return true;
+    };
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        let (weak, tag) = split_weak(if_range);
+        return !weak && etag.is_some_and(|etag| etag == tag);
+    }
+    match (httpdate::parse_http_date(if_range), last_modified) {
+        (Ok(date), Some(last_modified)) => date == last_modified,
+        _ => false,
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn header_date(headers: &HeaderMap, name: &str) -> Option<SystemTime> {
+    header_str(headers, name).and_then(|value| httpdate::parse_http_date(value).ok())
+}
+
+/// Checks an `If-Match`/`If-None-Match` list against the current tag, using
+/// strong comparison for `If-Match` and weak comparison otherwise.
+fn matches_any(list: &str, etag: Option<&str>, strong: bool) -> bool {
+    let Some(etag) = etag else {
+        return false;
+    };
+    if list.trim() == "*" {
+        return true;
+    }
+    let (current_weak, current) = split_weak(etag);
+    entity_tags(list).any(|(weak, tag)| {
+        if strong {
+            !weak && !current_weak && tag == current
+        } else {
+            tag == current
+        }
+    })
+}
+
+fn split_weak(tag: &str) -> (bool, &str) {
+    match tag.strip_prefix("W/") {
+        Some(tag) => (true, tag),
+        None => (false, tag),
+    }
+}
+
+/// Splits a comma-separated list of entity tags. Commas may appear inside the
+/// quoted part, so the list is scanned quote by quote rather than split.
+fn entity_tags(list: &str) -> impl Iterator<Item = (bool, &str)> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        let start = rest.find('"')?;
+        let weak = rest[..start]
+            .trim_start_matches([',', ' ', '\t'])
+            .ends_with("W/");
+        let end = start + 1 + rest[start + 1..].find('"')?;
+        let tag = &rest[start..=end];
+        rest = &rest[end + 1..];
+        Some((weak, tag))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const ETAG: &str = "\"abc\"";
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    // Sun, 06 Nov 1994 08:49:37 GMT
+    fn modified() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(784_111_777)
+    }
+
+    fn check(pairs: &[(&'static str, &'static str)]) -> Precondition {
+        evaluate(&headers(pairs), Some(ETAG), Some(modified()))
+    }
+
+    #[test]
+    fn splits_entity_tag_lists() {
+        let tags: Vec<_> = entity_tags("\"a\", W/\"b\",\"c,d\" ,W/\"\"").collect();
+        assert_eq!(
+            tags,
+            [
+                (false, "\"a\""),
+                (true, "\"b\""),
+                (false, "\"c,d\""),
+                (true, "\"\"")
+            ]
+        );
+        assert_eq!(entity_tags("").count(), 0);
+        assert_eq!(entity_tags("\"unterminated").count(), 0);
+    }
+
+    #[test]
+    fn formats_entity_tags() {
+        assert_eq!(strong_etag(&[0x01, 0xab]), "\"01ab\"");
+        assert!(weak_etag(b"body").starts_with("W/\""));
+        assert_eq!(weak_etag(b"body"), weak_etag(b"body"));
+        assert_ne!(weak_etag(b"body"), weak_etag(b"other"));
+    }
+
+    #[test]
+    fn proceeds_without_preconditions() {
+        assert_eq!(check(&[]), Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_match_compares_strongly() {
+        assert_eq!(check(&[("if-match", "\"abc\"")]), Precondition::Proceed);
+        assert_eq!(
+            check(&[("if-match", "\"x\", \"abc\"")]),
+            Precondition::Proceed
+        );
+        assert_eq!(check(&[("if-match", "*")]), Precondition::Proceed);
+        assert_eq!(check(&[("if-match", "W/\"abc\"")]), Precondition::Failed);
+        assert_eq!(check(&[("if-match", "\"x\"")]), Precondition::Failed);
+        assert_eq!(
+            evaluate(&headers(&[("if-match", "*")]), None, None),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        assert_eq!(
+            check(&[("if-none-match", "\"abc\"")]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            check(&[("if-none-match", "W/\"abc\"")]),
+            Precondition::NotModified
+        );
+        assert_eq!(check(&[("if-none-match", "*")]), Precondition::NotModified);
+        assert_eq!(check(&[("if-none-match", "\"x\"")]), Precondition::Proceed);
+        assert_eq!(
+            evaluate(
+                &headers(&[("if-none-match", "W/\"abc\"")]),
+                Some("W/\"abc\""),
+                None
+            ),
+            Precondition::NotModified
+        );
+    }
+
+    #[test]
+    fn compares_dates() {
+        let before = "Sun, 06 Nov 1994 08:49:36 GMT";
+        let at = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(
+            check(&[("if-modified-since", at)]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            check(&[("if-modified-since", before)]),
+            Precondition::Proceed
+        );
+        assert_eq!(check(&[("if-unmodified-since", at)]), Precondition::Proceed);
+        assert_eq!(
+            check(&[("if-unmodified-since", before)]),
+            Precondition::Failed
+        );
+        assert_eq!(
+            check(&[("if-modified-since", "yesterday")]),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn entity_tags_take_precedence_over_dates() {
+        let before = "Sun, 06 Nov 1994 08:49:36 GMT";
+        let at = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert_eq!(
+            check(&[("if-match", "\"abc\""), ("if-unmodified-since", before)]),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            check(&[("if-none-match", "\"x\""), ("if-modified-since", at)]),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn failed_if_match_wins_over_not_modified() {
+        assert_eq!(
+            check(&[("if-match", "\"x\""), ("if-none-match", "\"abc\"")]),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn if_range_matches_strong_tags_and_exact_dates() {
+        let matches = |value| {
+            if_range(
+                &headers(&[("if-range", value)]),
+                Some(ETAG),
+                Some(modified()),
+            )
+        };
+        assert!(matches("\"abc\""));
+        assert!(!matches("W/\"abc\""));
+        assert!(!matches("\"x\""));
+        assert!(matches("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert!(!matches("Sun, 06 Nov 1994 08:49:36 GMT"));
+        assert!(!matches("not a date"));
+        assert!(if_range(&HeaderMap::new(), Some(ETAG), None));
+        assert!(!if_range(
+            &headers(&[("if-range", "\"abc\"")]),
+            Some("W/\"abc\""),
+            None
+        ));
+    }
+}
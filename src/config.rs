@@ -0,0 +1,383 @@
+#[cfg(unix)]
+use crate::daemon::{self, Credentials, DaemonOptions};
+use crate::{
+    client_ip::{ForwardedHeader, IpNet},
+    conn_limit::WhenFull,
+    listener::{BindAddr, SocketOptions},
+    rate_limit::Rate,
+    trailing_slash::TrailingSlash,
+};
+use std::{env, fmt, time::Duration};
+
+const DEFAULT_LISTEN: &str = "0.0.0.0:3000";
+const DEFAULT_BACKLOG: u32 = 1024;
+const DEFAULT_SHUTDOWN_DELAY_SECS: u64 = 0;
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_WRITE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_HEADER_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_MAX_URI_LENGTH: usize = 8192;
+// Anything shorter would turn away ordinary page and asset URLs.
+const MIN_MAX_URI_LENGTH: usize = 256;
+
+const USAGE: &str = "\
+Usage: cerial [OPTIONS]
+
+Options:
+  --listen <ADDRS>           Comma-separated addresses to bind, or unix:<PATH> for a Unix socket [env: CERIAL_LISTEN] [default: 0.0.0.0:3000]
+                             Ignored when systemd passes sockets through LISTEN_FDS
+  --unix-mode <OCTAL>        Permissions for a Unix socket, e.g. 660 [env: CERIAL_UNIX_MODE]
+  --reuse-port <BOOL>        Set SO_REUSEPORT on TCP listeners [env: CERIAL_REUSE_PORT] [default: false]
+  --tcp-nodelay <BOOL>       Set TCP_NODELAY on connections [env: CERIAL_TCP_NODELAY] [default: true]
+  --backlog <N>              Listen backlog for TCP sockets [env: CERIAL_BACKLOG] [default: 1024]
+  --tcp-keepalive <SECS>     Send TCP keepalive probes after SECS idle [env: CERIAL_TCP_KEEPALIVE] [default: off]
+  --max-connections <N>      Most connections open at once across all listeners [env: CERIAL_MAX_CONNECTIONS] [default: unlimited]
+  --when-full <MODE>         wait: stop accepting; reject: answer 503 [env: CERIAL_WHEN_FULL] [default: wait]
+  --workers <N>              Number of runtime worker threads [env: CERIAL_WORKERS] [default: CPU count]
+  --shutdown-delay <SECS>    Seconds to keep serving, with /readyz failing, after a shutdown signal [env: CERIAL_SHUTDOWN_DELAY] [default: 0]
+  --drain-timeout <SECS>     Seconds to wait for connections on shutdown [env: CERIAL_DRAIN_TIMEOUT] [default: 10]
+  --read-timeout <SECS>      Seconds a read may stall, including keep-alive idle time [env: CERIAL_READ_TIMEOUT] [default: 30]
+  --write-timeout <SECS>     Seconds a write may stall [env: CERIAL_WRITE_TIMEOUT] [default: 30]
+  --header-timeout <SECS>    Seconds a client may take to send a request's headers [env: CERIAL_HEADER_TIMEOUT] [default: 10]
+  --handler-timeout <SECS>   Seconds a request may take before answering 503 [env: CERIAL_HANDLER_TIMEOUT] [default: off]
+  --max-uri-length <BYTES>   Longest request target accepted before answering 414, at least 256 [env: CERIAL_MAX_URI_LENGTH] [default: 8192]
+  --trusted-proxies <CIDRS>  Comma-separated proxies whose forwarding header is believed [env: CERIAL_TRUSTED_PROXIES]
+  --forwarded-header <NAME>  Header trusted proxies report clients in: forwarded or x-forwarded-for [env: CERIAL_FORWARDED_HEADER] [default: x-forwarded-for]
+  --rate-limit <N/SECS>      Allow each client N requests per SECS seconds [env: CERIAL_RATE_LIMIT] [default: unlimited]
+  --trailing-slash <MODE>    ignore: serve /page/ as /page; redirect: answer 308 to /page [env: CERIAL_TRAILING_SLASH] [default: ignore]
+  --cache-max-age <SECS>     Let clients and caches reuse pages and assets for SECS seconds [env: CERIAL_CACHE_MAX_AGE] [default: off]
+  --metrics-path <PATH>      Serve Prometheus metrics at this path, in place of any page there [env: CERIAL_METRICS_PATH] [default: disabled]
+  --health-checks <BOOL>     Serve /healthz and /readyz [env: CERIAL_HEALTH_CHECKS] [default: false]
+  --daemonize <BOOL>         Fork into the background and detach from the terminal [env: CERIAL_DAEMONIZE] [default: false]
+  --pid-file <PATH>          Write the process ID to PATH [env: CERIAL_PID_FILE]
+  --log-file <PATH>          Append stdout and stderr to PATH [env: CERIAL_LOG_FILE]
+  --user <NAME>              Switch to this user after binding [env: CERIAL_USER]
+  --group <NAME>             Switch to this group after binding [env: CERIAL_GROUP] [default: the user's primary group]
+  -h, --help                 Print this help";
+
+#[derive(Debug)]
+pub struct ServerConfig {
+    pub listen: Vec<BindAddr>,
+    pub unix_mode: Option<u32>,
+    pub socket: SocketOptions,
+    pub max_connections: Option<usize>,
+    pub when_full: WhenFull,
+    pub workers: Option<usize>,
+    pub shutdown_delay: Duration,
+    pub drain_timeout: Duration,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    pub header_timeout: Duration,
+    pub handler_timeout: Option<Duration>,
+    pub max_uri_length: usize,
+    pub trusted_proxies: Vec<IpNet>,
+    pub forwarded_header: ForwardedHeader,
+    pub rate_limit: Option<Rate>,
+    pub trailing_slash: TrailingSlash,
+    pub cache_max_age: Option<Duration>,
+    pub metrics_path: Option<String>,
+    pub health_checks: bool,
+    #[cfg(unix)]
+    pub daemon: DaemonOptions,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Help,
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { name: String, value: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "{}", USAGE),
+            ConfigError::UnknownFlag(flag) => write!(f, "unknown option '{}'\n\n{}", flag, USAGE),
+            ConfigError::MissingValue(flag) => write!(f, "option '{}' requires a value", flag),
+            ConfigError::InvalidValue { name, value } => {
+                write!(f, "invalid value '{}' for {}", value, name)
+            }
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Command-line flags take precedence over `CERIAL_*` environment variables,
+    /// which take precedence over the built-in defaults.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut listen = from_env("CERIAL_LISTEN");
+        let mut unix_mode = from_env("CERIAL_UNIX_MODE");
+        let mut reuse_port = from_env("CERIAL_REUSE_PORT");
+        let mut nodelay = from_env("CERIAL_TCP_NODELAY");
+        let mut backlog = from_env("CERIAL_BACKLOG");
+        let mut keepalive = from_env("CERIAL_TCP_KEEPALIVE");
+        let mut max_connections = from_env("CERIAL_MAX_CONNECTIONS");
+        let mut when_full = from_env("CERIAL_WHEN_FULL");
+        let mut workers = from_env("CERIAL_WORKERS");
+        let mut shutdown_delay = from_env("CERIAL_SHUTDOWN_DELAY");
+        let mut drain_timeout = from_env("CERIAL_DRAIN_TIMEOUT");
+        let mut read_timeout = from_env("CERIAL_READ_TIMEOUT");
+        let mut write_timeout = from_env("CERIAL_WRITE_TIMEOUT");
+        let mut header_timeout = from_env("CERIAL_HEADER_TIMEOUT");
+        let mut handler_timeout = from_env("CERIAL_HANDLER_TIMEOUT");
+        let mut max_uri_length = from_env("CERIAL_MAX_URI_LENGTH");
+        let mut trusted_proxies = from_env("CERIAL_TRUSTED_PROXIES");
+        let mut forwarded_header = from_env("CERIAL_FORWARDED_HEADER");
+        let mut rate_limit = from_env("CERIAL_RATE_LIMIT");
+        let mut trailing_slash = from_env("CERIAL_TRAILING_SLASH");
+        let mut cache_max_age = from_env("CERIAL_CACHE_MAX_AGE");
+        let mut metrics_path = from_env("CERIAL_METRICS_PATH");
+        let mut health_checks = from_env("CERIAL_HEALTH_CHECKS");
+        let mut daemonize = from_env("CERIAL_DAEMONIZE");
+        let mut pid_file = from_env("CERIAL_PID_FILE");
+        let mut log_file = from_env("CERIAL_LOG_FILE");
+        let mut user = from_env("CERIAL_USER");
+        let mut group = from_env("CERIAL_GROUP");
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let slot = match arg.as_str() {
+                "-h" | "--help" => return Err(ConfigError::Help),
+                "--listen" => &mut listen,
+                "--unix-mode" => &mut unix_mode,
+                "--reuse-port" => &mut reuse_port,
+                "--tcp-nodelay" => &mut nodelay,
+                "--backlog" => &mut backlog,
+                "--tcp-keepalive" => &mut keepalive,
+                "--max-connections" => &mut max_connections,
+                "--when-full" => &mut when_full,
+                "--workers" => &mut workers,
+                "--shutdown-delay" => &mut shutdown_delay,
+                "--drain-timeout" => &mut drain_timeout,
+                "--read-timeout" => &mut read_timeout,
+                "--write-timeout" => &mut write_timeout,
+                "--header-timeout" => &mut header_timeout,
+                "--handler-timeout" => &mut handler_timeout,
+                "--max-uri-length" => &mut max_uri_length,
+                "--trusted-proxies" => &mut trusted_proxies,
+                "--forwarded-header" => &mut forwarded_header,
+                "--rate-limit" => &mut rate_limit,
+                "--trailing-slash" => &mut trailing_slash,
+                "--cache-max-age" => &mut cache_max_age,
+                "--metrics-path" => &mut metrics_path,
+                "--health-checks" => &mut health_checks,
+                "--daemonize" => &mut daemonize,
+                "--pid-file" => &mut pid_file,
+                "--log-file" => &mut log_file,
+                "--user" => &mut user,
+                "--group" => &mut group,
+                _ => return Err(ConfigError::UnknownFlag(arg)),
+            };
+            let value = args
+                .next()
+                .ok_or_else(|| ConfigError::MissingValue(arg.clone()))?;
+            *slot = Some((arg, value));
+        }
+
+        let listen = match listen {
+            Some((name, value)) => value
+                .split(',')
+                .map(|addr| parse_value(&name, addr.trim()))
+                .collect::<Result<_, _>>()?,
+            None => vec![DEFAULT_LISTEN.parse().unwrap()],
+        };
+        let unix_mode = match unix_mode {
+            Some((name, value)) => match u32::from_str_radix(&value, 8) {
+                Ok(mode) if mode <= 0o777 => Some(mode),
+                _ => return Err(invalid(&name, &value)),
+            },
+            None => None,
+        };
+        let socket = SocketOptions {
+            reuse_port: match reuse_port {
+                Some((name, value)) => parse_value(&name, &value)?,
+                None => false,
+            },
+            nodelay: match nodelay {
+                Some((name, value)) => parse_value(&name, &value)?,
+                None => true,
+            },
+            backlog: match backlog {
+                Some((name, value)) => parse_value(&name, &value)?,
+                None => DEFAULT_BACKLOG,
+            },
+            keepalive: match keepalive {
+                Some((name, value)) => Some(Duration::from_secs(parse_value(&name, &value)?)),
+                None => None,
+            },
+        };
+        let max_connections = match max_connections {
+            Some((name, value)) => match parse_value::<usize>(&name, &value)? {
+                0 => return Err(invalid(&name, &value)),
+                n => Some(n),
+            },
+            None => None,
+        };
+        let when_full = match when_full {
+            Some((name, value)) => parse_value(&name, &value)?,
+            None => WhenFull::Wait,
+        };
+        let workers = match workers {
+            Some((name, value)) => match parse_value::<usize>(&name, &value)? {
+                0 => return Err(invalid(&name, &value)),
+                n => Some(n),
+            },
+            None => None,
+        };
+        let shutdown_delay = parse_secs(shutdown_delay, DEFAULT_SHUTDOWN_DELAY_SECS)?;
+        let drain_timeout = parse_secs(drain_timeout, DEFAULT_DRAIN_TIMEOUT_SECS)?;
+        let read_timeout = parse_timeout(read_timeout, DEFAULT_READ_TIMEOUT_SECS)?;
+        let write_timeout = parse_timeout(write_timeout, DEFAULT_WRITE_TIMEOUT_SECS)?;
+        let header_timeout = parse_timeout(header_timeout, DEFAULT_HEADER_TIMEOUT_SECS)?;
+        let handler_timeout = match handler_timeout {
+            Some((name, value)) => match parse_value::<u64>(&name, &value)? {
+                0 => return Err(invalid(&name, &value)),
+                secs => Some(Duration::from_secs(secs)),
+            },
+            None => None,
+        };
+        let max_uri_length = match max_uri_length {
+            Some((name, value)) => match parse_value(&name, &value)? {
+                len if len < MIN_MAX_URI_LENGTH => return Err(invalid(&name, &value)),
+                len => len,
+            },
+            None => DEFAULT_MAX_URI_LENGTH,
+        };
+        let trusted_proxies = match trusted_proxies {
+            Some((name, value)) => value
+                .split(',')
+                .map(|net| net.trim().parse().map_err(|_| invalid(&name, net)))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        let forwarded_header = match forwarded_header {
+            Some((name, value)) => parse_value(&name, &value)?,
+            None => ForwardedHeader::XForwardedFor,
+        };
+        let rate_limit = match rate_limit {
+            Some((name, value)) => Some(parse_value(&name, &value)?),
+            None => None,
+        };
+        let trailing_slash = match trailing_slash {
+            Some((name, value)) => parse_value(&name, &value)?,
+            None => TrailingSlash::Ignore,
+        };
+        let cache_max_age = match cache_max_age {
+            Some((name, value)) => Some(Duration::from_secs(parse_value(&name, &value)?)),
+            None => None,
+        };
+        let health_checks = match health_checks {
+            Some((name, value)) => parse_value(&name, &value)?,
+            None => false,
+        };
+        let metrics_path = match metrics_path {
+            Some((name, value)) if !is_free_route(&value, health_checks) => {
+                return Err(invalid(&name, &value));
+            }
+            Some((_, value)) => Some(value),
+            None => None,
+        };
+        #[cfg(unix)]
+        let daemon = DaemonOptions {
+            daemonize: match daemonize {
+                Some((name, value)) => parse_value(&name, &value)?,
+                None => false,
+            },
+            pid_file: pid_file.map(|(_, value)| value.into()),
+            log_file: log_file.map(|(_, value)| value.into()),
+            credentials: {
+                let user = match user {
+                    Some((name, value)) => {
+                        Some(daemon::lookup_user(&value).ok_or_else(|| invalid(&name, &value))?)
+                    }
+                    None => None,
+                };
+                let gid = match group {
+                    Some((name, value)) => {
+                        Some(daemon::lookup_group(&value).ok_or_else(|| invalid(&name, &value))?)
+                    }
+                    None => user.map(|(_, gid)| gid),
+                };
+                match (user, gid) {
+                    (None, None) => None,
+                    (user, gid) => Some(Credentials {
+                        uid: user.map(|(uid, _)| uid),
+                        gid,
+                    }),
+                }
+            },
+        };
+
+        Ok(ServerConfig {
+            listen,
+            unix_mode,
+            socket,
+            max_connections,
+            when_full,
+            workers,
+            shutdown_delay,
+            drain_timeout,
+            read_timeout,
+            write_timeout,
+            header_timeout,
+            handler_timeout,
+            max_uri_length,
+            trusted_proxies,
+            forwarded_header,
+            rate_limit,
+            trailing_slash,
+            cache_max_age,
+            metrics_path,
+            health_checks,
+            #[cfg(unix)]
+            daemon,
+        })
+    }
+}
+
+fn from_env(var: &str) -> Option<(String, String)> {
+    env::var(var).ok().map(|value| (var.to_string(), value))
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| invalid(name, value))
+}
+
+fn parse_secs(setting: Option<(String, String)>, default: u64) -> Result<Duration, ConfigError> {
+    match setting {
+        Some((name, value)) => Ok(Duration::from_secs(parse_value(&name, &value)?)),
+        None => Ok(Duration::from_secs(default)),
+    }
+}
+
+/// Whether `path` can be routed without clashing with the home page or the
+/// health checks, or being read by axum as a capture.
+fn is_free_route(path: &str, health_checks: bool) -> bool {
+    path.starts_with('/')
+        && path != "/"
+        && !(health_checks && matches!(path, "/healthz" | "/readyz"))
+        && !path.contains(['{', '}'])
+        && !path
+            .split('/')
+            .any(|segment| segment.starts_with([':', '*']))
+}
+
+/// Like `parse_secs`, but rejects zero, which would time out every connection
+/// straight away.
+fn parse_timeout(setting: Option<(String, String)>, default: u64) -> Result<Duration, ConfigError> {
+    match setting {
+        Some((name, value)) => match parse_value::<u64>(&name, &value)? {
+            0 => Err(invalid(&name, &value)),
+            secs => Ok(Duration::from_secs(secs)),
+        },
+        None => Ok(Duration::from_secs(default)),
+    }
+}
+
+fn invalid(name: &str, value: &str) -> ConfigError {
+    ConfigError::InvalidValue {
+        name: name.to_string(),
+        value: value.to_string(),
+    }
+}
@@ -0,0 +1,158 @@
+use crate::metrics::Metrics;
+use axum::serve::Listener;
+use std::{
+    io,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+
+// A rejected client that will not read our 503 is dropped after this long.
+const REJECT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SERVICE_UNAVAILABLE: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\ncontent-length: 0\r\n\r\n";
+
+/// What to do with new connections once the limit is reached.
+#[derive(Clone, Copy, Debug)]
+pub enum WhenFull {
+    /// Stop accepting and let the kernel backlog absorb the burst.
+    Wait,
+    /// Accept, answer 503 Service Unavailable, and close.
+    Reject,
+}
+
+impl FromStr for WhenFull {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wait" => Ok(WhenFull::Wait),
+            "reject" => Ok(WhenFull::Reject),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Caps the number of open connections. The permits are shared, so one
+/// semaphore can bound several listeners together.
+pub struct LimitListener<L> {
+    inner: L,
+    permits: Arc<Semaphore>,
+    when_full: WhenFull,
+    metrics: Arc<Metrics>,
+}
+
+impl<L> LimitListener<L> {
+    pub fn new(
+        inner: L,
+        permits: Arc<Semaphore>,
+        when_full: WhenFull,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        LimitListener {
+            inner,
+            permits,
+            when_full,
+            metrics,
+        }
+    }
+}
+
+impl<L: Listener> Listener for LimitListener<L> {
+    type Io = LimitedIo<L::Io>;
+    type Addr = L::Addr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let permit = match self.when_full {
+                WhenFull::Wait => Some(
+                    self.permits
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("connection semaphore is never closed"),
+                ),
+                WhenFull::Reject => None,
+            };
+            let (mut io, addr) = self.inner.accept().await;
+            let permit = match permit {
+                Some(permit) => permit,
+                None => match self.permits.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        self.metrics.record_rejected_connection();
+                        tokio::spawn(tokio::time::timeout(REJECT_WRITE_TIMEOUT, async move {
+                            let _ = io.write_all(SERVICE_UNAVAILABLE).await;
+                            let _ = io.shutdown().await;
+                        }));
+                        continue;
+                    }
+                },
+            };
+            return (
+                LimitedIo {
+                    inner: io,
+                    _permit: permit,
+                },
+                addr,
+            );
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+/// A connection holding one of the limiter's permits until it is dropped.
+pub struct LimitedIo<T> {
+    inner: T,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for LimitedIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for LimitedIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    ffi::CString,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, PipeReader, PipeWriter, Read, Write},
+    os::fd::AsRawFd,
+    path::{self, PathBuf},
+};
+
+/// How to run as a classic background service.
+#[derive(Clone, Debug, Default)]
+pub struct DaemonOptions {
+    /// Fork into the background and detach from the terminal.
+    pub daemonize: bool,
+    pub pid_file: Option<PathBuf>,
+    /// Where stdout and stderr go. A daemon without one discards its output.
+    pub log_file: Option<PathBuf>,
+    /// Who to run as once the listeners are bound.
+    pub credentials: Option<Credentials>,
+}
+
+/// Removes the PID file when dropped.
+pub struct PidFile(PathBuf);
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// The user and group to switch to after binding.
+#[derive(Clone, Copy, Debug)]
+pub struct Credentials {
+    pub uid: Option<libc::uid_t>,
+    pub gid: Option<libc::gid_t>,
+}
+
+impl Credentials {
+    /// Gives up root. Supplementary groups are cleared, then the group is
+    /// changed before the user, since afterwards we may no longer be allowed.
+    pub fn apply(&self) -> io::Result<()> {
+        if let Some(gid) = self.gid {
+            cvt(unsafe { libc::setgroups(1, &gid) })?;
+            cvt(unsafe { libc::setgid(gid) })?;
+        }
+        if let Some(uid) = self.uid {
+            cvt(unsafe { libc::setuid(uid) })?;
+        }
+        Ok(())
+    }
+}
+
+/// What `start` leaves for the rest of startup to finish.
+pub struct Started {
+    pub pid_file: Option<PidFile>,
+    /// Set in a daemon, whose parent is still waiting to hear whether the
+    /// server came up.
+    pub startup: Option<Startup>,
+}
+
+/// The daemon's end of the pipe its parent waits on. The parent exits with
+/// the outcome reported here, or with failure if the daemon dies first.
+pub struct Startup(PipeWriter);
+
+impl Startup {
+    pub fn succeeded(mut self) {
+        let _ = self.0.write_all(&[0]);
+    }
+
+    /// Hands `err` to the parent, which prints it on the terminal the daemon
+    /// no longer has.
+    pub fn failed(mut self, err: impl fmt::Display) {
+        let _ = self.0.write_all(format!("\x01{}", err).as_bytes());
+    }
+}
+
+/// Daemonizes if asked, redirects output to the log file, and writes the PID
+/// file. Must run before the runtime starts any threads, since only the
+/// calling thread survives a fork.
+///
+/// The parent of a daemon does not return: it exits once the daemon reports
+/// through `Started::startup` that it is serving, or that it failed to.
+pub fn start(options: &DaemonOptions) -> io::Result<Started> {
+    // Open the log before forking so a bad path is still reported on the
+    // terminal.
+    let log = match &options.log_file {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+    // A daemon moves to `/`, so the path has to be resolved before then.
+    let pid_path = options
+        .pid_file
+        .as_deref()
+        .map(path::absolute)
+        .transpose()?;
+
+    if !options.daemonize {
+        if let Some(log) = &log {
+            redirect(log, libc::STDOUT_FILENO)?;
+            redirect(log, libc::STDERR_FILENO)?;
+        }
+        return Ok(Started {
+            pid_file: write_pid_file(pid_path)?,
+            startup: None,
+        });
+    }
+
+    let (reader, writer) = io::pipe()?;
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => drop(reader),
+        _ => {
+            drop(writer);
+            std::process::exit(wait_for_startup(reader));
+        }
+    }
+    let startup = Startup(writer);
+    match detach(log.as_ref()).and_then(|()| write_pid_file(pid_path)) {
+        Ok(pid_file) => Ok(Started {
+            pid_file,
+            startup: Some(startup),
+        }),
+        Err(err) => {
+            startup.failed(&err);
+            Err(err)
+        }
+    }
+}
+
+/// Blocks until the daemon reports on startup or exits, and returns the
+/// status the parent should exit with.
+fn wait_for_startup(mut reader: PipeReader) -> i32 {
+    let mut report = Vec::new();
+    let _ = reader.read_to_end(&mut report);
+    match report.split_first() {
+        Some((0, _)) => 0,
+        Some((_, message)) => {
+            eprintln!("error: {}", String::from_utf8_lossy(message));
+            1
+        }
+        None => {
+            eprintln!("error: server exited during startup");
+            1
+        }
+    }
+}
+
+/// Starts a new session without a controlling terminal, leaves the working
+/// directory so it is not kept busy, and points the standard streams at the
+/// log or `/dev/null`.
+fn detach(log: Option<&File>) -> io::Result<()> {
+    cvt(unsafe { libc::setsid() })?;
+    std::env::set_current_dir("/")?;
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    redirect(&null, libc::STDIN_FILENO)?;
+    let out = log.unwrap_or(&null);
+    redirect(out, libc::STDOUT_FILENO)?;
+    redirect(out, libc::STDERR_FILENO)
+}
+
+fn write_pid_file(path: Option<PathBuf>) -> io::Result<Option<PidFile>> {
+    match path {
+        Some(path) => {
+            fs::write(&path, format!("{}\n", std::process::id()))?;
+            Ok(Some(PidFile(path)))
+        }
+        None => Ok(None),
+    }
+}
+
+fn redirect(file: &File, fd: libc::c_int) -> io::Result<()> {
+    cvt(unsafe { libc::dup2(file.as_raw_fd(), fd) }).map(|_| ())
+}
+
+/// Returns the uid and primary gid of the named user. `getpwnam` is not
+/// thread-safe, so this is only called while loading the configuration.
+pub fn lookup_user(name: &str) -> Option<(libc::uid_t, libc::gid_t)> {
+    let name = CString::new(name).ok()?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return None;
+    }
+    Some(unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) })
+}
+
+pub fn lookup_group(name: &str) -> Option<libc::gid_t> {
+    let name = CString::new(name).ok()?;
+    let group = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group.is_null() {
+        return None;
+    }
+    Some(unsafe { (*group).gr_gid })
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
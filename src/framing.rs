@@ -0,0 +1,31 @@
+use axum::{
+    extract::Request,
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Rejects requests that carry both `Transfer-Encoding` and `Content-Length`.
+///
+/// hyper frames such requests by `Transfer-Encoding` as RFC 9112 allows, but a
+/// proxy in front of us may have used `Content-Length`, which is the classic
+/// request smuggling setup. Refusing them and closing the connection keeps the
+/// two from disagreeing about where the next request starts.
+///
+/// Only requests that list `Content-Length` first can be caught here. hyper
+/// discards a `Content-Length` that follows `Transfer-Encoding` while parsing
+/// the head, so those requests arrive looking like plain chunked requests.
+pub async fn reject_ambiguous_framing(request: Request, next: Next) -> Response {
+    let headers = request.headers();
+    if headers.contains_key(header::TRANSFER_ENCODING)
+        && headers.contains_key(header::CONTENT_LENGTH)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            [(header::CONNECTION, "close")],
+            "Bad Request",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
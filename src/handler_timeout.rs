@@ -0,0 +1,29 @@
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::time::Duration;
+
+// Seconds a client is told to wait before retrying a request that timed out.
+const RETRY_AFTER_SECS: u64 = 1;
+
+/// Answers 503 Service Unavailable with `Retry-After` when producing a
+/// response takes longer than `limit`. The handler's future is dropped at
+/// its next await point; work that never yields cannot be interrupted.
+pub async fn limit_handler_time(
+    State(limit): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match tokio::time::timeout(limit, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+            "Service Unavailable",
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,39 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+const JSON: [(&str, &str); 1] = [("content-type", "application/json")];
+
+/// Whether the server should be sent new traffic. Readiness is dropped as
+/// soon as shutdown begins so load balancers stop routing here while
+/// in-flight requests finish.
+#[derive(Default)]
+pub struct Health {
+    draining: AtomicBool,
+}
+
+impl Health {
+    pub fn set_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Liveness: the process is up and answering requests.
+pub async fn serve_liveness() -> impl IntoResponse {
+    (JSON, r#"{"status":"ok"}"#)
+}
+
+/// Readiness: 200 while serving, 503 once shutdown has begun.
+pub async fn serve_readiness(State(health): State<Arc<Health>>) -> impl IntoResponse {
+    if health.draining.load(Ordering::Relaxed) {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            JSON,
+            r#"{"status":"draining"}"#,
+        )
+    } else {
+        (StatusCode::OK, JSON, r#"{"status":"ready"}"#)
+    }
+}
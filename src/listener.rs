@@ -0,0 +1,363 @@
+use crate::timeout::TimeoutListener;
+use axum::{
+    extract::connect_info::Connected,
+    serve::{IncomingStream, Listener},
+};
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    fmt, io,
+    net::SocketAddr,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpSocket, TcpStream},
+};
+#[cfg(unix)]
+use {
+    socket2::{Socket, Type},
+    std::{
+        env, fs,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        os::unix::fs::{FileTypeExt, PermissionsExt},
+        path::PathBuf,
+    },
+    tokio::net::{UnixListener, UnixStream},
+};
+
+// The first file descriptor systemd passes for socket activation.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Where to accept connections: a TCP socket address, or `unix:<path>` for a
+/// Unix domain socket.
+#[derive(Clone, Debug)]
+pub enum BindAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddr {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Resolved now, since a daemon leaves the working directory before
+        // binding.
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            return std::path::absolute(path)
+                .map(BindAddr::Unix)
+                .map_err(|_| ());
+        }
+        s.parse().map(BindAddr::Tcp).map_err(|_| ())
+    }
+}
+
+impl fmt::Display for BindAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindAddr::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            BindAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Options applied to TCP listening sockets and the connections they accept.
+#[derive(Clone, Copy, Debug)]
+pub struct SocketOptions {
+    /// Set `SO_REUSEPORT` so several processes can share the port.
+    pub reuse_port: bool,
+    /// Set `TCP_NODELAY` on accepted connections, disabling Nagle's algorithm.
+    pub nodelay: bool,
+    pub backlog: u32,
+    /// Idle time before keepalive probes are sent, and the interval between
+    /// them. `None` leaves keepalive off.
+    pub keepalive: Option<Duration>,
+}
+
+impl SocketOptions {
+    fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(keepalive) = self.keepalive {
+            let params = TcpKeepalive::new()
+                .with_time(keepalive)
+                .with_interval(keepalive);
+            SockRef::from(stream).set_tcp_keepalive(&params)?;
+        }
+        Ok(())
+    }
+}
+
+/// Address of the peer that opened the connection a request arrived on.
+#[derive(Clone, Copy, Debug)]
+pub enum RemoteAddr {
+    Tcp(SocketAddr),
+    /// A local process connected over a Unix domain socket.
+    Unix,
+}
+
+impl<L> Connected<IncomingStream<'_, TimeoutListener<L>>> for RemoteAddr
+where
+    L: Listener<Addr = RemoteAddr>,
+{
+    fn connect_info(stream: IncomingStream<'_, TimeoutListener<L>>) -> Self {
+        *stream.remote_addr()
+    }
+}
+
+pub enum ServerListener {
+    Tcp {
+        listener: TcpListener,
+        options: SocketOptions,
+    },
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        /// Socket file to remove on drop; `None` for sockets we did not
+        /// create.
+        path: Option<PathBuf>,
+    },
+}
+
+impl ServerListener {
+    /// Binds `addr`. A stale socket file left at a Unix socket path is
+    /// replaced, while one another server still listens on fails with
+    /// `AddrInUse`. `unix_mode` sets the new socket's permission bits.
+    pub fn bind(
+        addr: &BindAddr,
+        options: SocketOptions,
+        unix_mode: Option<u32>,
+    ) -> io::Result<Self> {
+        match addr {
+            BindAddr::Tcp(addr) => {
+                let socket = if addr.is_ipv4() {
+                    TcpSocket::new_v4()?
+                } else {
+                    TcpSocket::new_v6()?
+                };
+                // Linux binds `[::]` dual-stack by default, which would take the
+                // IPv4 port as well and clash with a separate `0.0.0.0` listener.
+                if addr.is_ipv6() {
+                    SockRef::from(&socket).set_only_v6(true)?;
+                }
+                #[cfg(unix)]
+                {
+                    socket.set_reuseaddr(true)?;
+                    socket.set_reuseport(options.reuse_port)?;
+                }
+                socket.bind(*addr)?;
+                Ok(ServerListener::Tcp {
+                    listener: socket.listen(options.backlog)?,
+                    options,
+                })
+            }
+            #[cfg(unix)]
+            BindAddr::Unix(path) => {
+                // Only a socket nobody is listening on is stale. Removing a live
+                // one would leave the server behind it unreachable.
+                if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    match std::os::unix::net::UnixStream::connect(path) {
+                        Ok(_) => return Err(io::ErrorKind::AddrInUse.into()),
+                        Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                            fs::remove_file(path)?
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                let listener = UnixListener::bind(path)?;
+                if let Some(mode) = unix_mode {
+                    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+                }
+                Ok(ServerListener::Unix {
+                    listener,
+                    path: Some(path.clone()),
+                })
+            }
+        }
+    }
+
+    /// Takes over the sockets systemd passed for socket activation. Returns
+    /// an empty list unless `LISTEN_PID` names this process.
+    #[cfg(unix)]
+    pub fn from_systemd(options: SocketOptions) -> io::Result<Vec<Self>> {
+        let for_us = env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let count: RawFd = match env::var("LISTEN_FDS") {
+            Ok(count) if for_us => count
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid LISTEN_FDS"))?,
+            _ => 0,
+        };
+
+        (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+            .map(|fd| {
+                // SAFETY: systemd passes descriptors from SD_LISTEN_FDS_START on
+                // to the process named in LISTEN_PID, checked above, and nothing
+                // else in this process opens or wraps them. This is the only
+                // call, so each descriptor gets exactly one owner.
+                let socket = unsafe { Socket::from_raw_fd(fd) };
+                let is_listener = socket.r#type().is_ok_and(|ty| ty == Type::STREAM)
+                    && is_listening(&socket).unwrap_or(false);
+                if !is_listener {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("file descriptor {} is not a listening stream socket", fd),
+                    ));
+                }
+                socket.set_nonblocking(true)?;
+                if socket.local_addr()?.is_unix() {
+                    Ok(ServerListener::Unix {
+                        listener: UnixListener::from_std(OwnedFd::from(socket).into())?,
+                        path: None,
+                    })
+                } else {
+                    Ok(ServerListener::Tcp {
+                        listener: TcpListener::from_std(socket.into())?,
+                        options,
+                    })
+                }
+            })
+            .collect()
+    }
+}
+
+/// Whether `listen(2)` has been called on `socket`.
+#[cfg(unix)]
+fn is_listening(socket: &Socket) -> io::Result<bool> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `value` and `len` describe a buffer of the size SO_ACCEPTCONN
+    // reports into.
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            (&mut value as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value != 0)
+}
+
+#[cfg(unix)]
+impl Drop for ServerListener {
+    fn drop(&mut self) {
+        if let ServerListener::Unix {
+            path: Some(path), ..
+        } = self
+        {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Listener for ServerListener {
+    type Io = Connection;
+    type Addr = RemoteAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self {
+            ServerListener::Tcp { listener, options } => {
+                let (stream, addr) = Listener::accept(listener).await;
+                if let Err(err) = options.apply(&stream) {
+                    eprintln!("failed to set socket options for {}: {}", addr, err);
+                }
+                (Connection::Tcp(stream), RemoteAddr::Tcp(addr))
+            }
+            #[cfg(unix)]
+            ServerListener::Unix { listener, .. } => {
+                let (stream, _) = Listener::accept(listener).await;
+                (Connection::Unix(stream), RemoteAddr::Unix)
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        match self {
+            ServerListener::Tcp { listener, .. } => listener.local_addr().map(RemoteAddr::Tcp),
+            #[cfg(unix)]
+            ServerListener::Unix { .. } => Ok(RemoteAddr::Unix),
+        }
+    }
+}
+
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Tcp(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Connection::Tcp(stream) => stream.is_write_vectored(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.is_write_vectored(),
+        }
+    }
+}
@@ -1,13 +1,55 @@
+mod access_log;
+mod cache_control;
+mod client_ip;
+mod compress;
+mod conditional;
+mod config;
+mod conn_limit;
+#[cfg(unix)]
+mod daemon;
+mod framing;
+mod handler_timeout;
+mod health;
+mod listener;
+mod metrics;
+mod range;
+mod rate_limit;
+mod request_id;
+mod timeout;
+mod trailing_slash;
+mod uri_limit;
+
 use axum::{
     Router,
     body::Body,
     extract::Path,
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{Html, Response},
     routing::get,
 };
+use client_ip::TrustedProxies;
+use config::{ConfigError, ServerConfig};
+use conn_limit::LimitListener;
+use health::Health;
+use listener::{RemoteAddr, ServerListener};
+use metrics::{CountingListener, Metrics};
 use pulldown_cmark::{Parser, html};
-use rust_embed::RustEmbed;
+use range::RangeRequest;
+use rate_limit::RateLimiter;
+use rust_embed::{EmbeddedFile, RustEmbed};
+use std::{
+    future::IntoFuture,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+use timeout::TimeoutListener;
+use tokio::{
+    signal,
+    sync::{Semaphore, watch},
+    task::JoinSet,
+};
+use trailing_slash::TrailingSlash;
 
 #[derive(RustEmbed)]
 #[folder = "pages/"]
@@ -26,6 +68,28 @@ fn get_content_type(path: &str) -> &'static str {
         "image/gif"
     } else if path.ends_with(".svg") {
         "image/svg+xml"
+    } else if path.ends_with(".webp") {
+        "image/webp"
+    } else if path.ends_with(".ico") {
+        "image/x-icon"
+    } else if path.ends_with(".css") {
+        "text/css; charset=utf-8"
+    } else if path.ends_with(".js") {
+        "text/javascript; charset=utf-8"
+    } else if path.ends_with(".json") {
+        "application/json"
+    } else if path.ends_with(".txt") {
+        "text/plain; charset=utf-8"
+    } else if path.ends_with(".pdf") {
+        "application/pdf"
+    } else if path.ends_with(".mp4") {
+        "video/mp4"
+    } else if path.ends_with(".webm") {
+        "video/webm"
+    } else if path.ends_with(".mp3") {
+        "audio/mpeg"
+    } else if path.ends_with(".woff2") {
+        "font/woff2"
     } else {
         "application/octet-stream"
     }
@@ -46,7 +110,89 @@ async fn home() -> Html<String> {
     Html(html)
 }
 
-async fn serve_file(Path(full_path): Path<String>) -> Result<Response, StatusCode> {
+fn serve_asset(
+    file_path: &str,
+    file: EmbeddedFile,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let content_type = get_content_type(file_path);
+    let last_modified = file
+        .metadata
+        .last_modified()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    let etag = conditional::strong_etag(&file.metadata.sha256_hash());
+    let mut response = Response::builder()
+        .header("accept-ranges", "bytes")
+        .header("etag", &etag);
+    if let Some(last_modified) = last_modified {
+        response = response.header("last-modified", httpdate::fmt_http_date(last_modified));
+    }
+    if let Some(status) = conditional::evaluate(headers, Some(&etag), last_modified).status() {
+        return response
+            .status(status)
+            .body(Body::empty())
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    let len = file.data.len();
+    let range = headers
+        .get("range")
+        .and_then(|value| value.to_str().ok())
+        .filter(|_| conditional::if_range(headers, Some(&etag), last_modified))
+        .map_or(RangeRequest::Ignore, |value| range::parse_range(value, len));
+    let response = match range {
+        RangeRequest::Ignore => response
+            .header("content-type", content_type)
+            .body(Body::from(file.data)),
+        RangeRequest::Unsatisfiable => response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header("content-range", format!("bytes */{}", len))
+            .body(Body::empty()),
+        RangeRequest::Satisfiable(ranges) if ranges.len() == 1 => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("content-type", content_type)
+            .header("content-range", range::content_range(&ranges[0], len))
+            .body(Body::from(file.data[ranges[0].clone()].to_vec())),
+        RangeRequest::Satisfiable(ranges) => {
+            let boundary = range::boundary();
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    "content-type",
+                    format!("multipart/byteranges; boundary={}", boundary),
+                )
+                .body(Body::from(range::multipart_body(
+                    &file.data,
+                    &ranges,
+                    content_type,
+                    &boundary,
+                )))
+        }
+    };
+    response.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Collapses empty and `.` segments and resolves `..`, returning `None` if the
+/// path would climb above the root.
+fn normalize_path(path: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            _ => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+async fn serve_file(
+    Path(full_path): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let full_path = normalize_path(&full_path).ok_or(StatusCode::BAD_REQUEST)?;
     let parts: Vec<&str> = full_path.splitn(2, '/').collect();
     let name = parts[0];
     let path = parts.get(1).copied().unwrap_or("");
@@ -74,13 +220,20 @@ async fn serve_file(Path(full_path): Path<String>) -> Result<Response, StatusCod
             };
             template_str = template_str.replace("{custom_css}", &custom_css);
             let full_html = template_str.replace("{content}", &html_output);
-            Ok(Html(full_html).into_response())
-        } else {
-            let content_type = get_content_type(&file_path);
-            Ok(Response::builder()
-                .header("content-type", content_type)
-                .body(Body::from(file.data))
+            let etag = conditional::weak_etag(full_html.as_bytes());
+            let response = Response::builder().header("etag", &etag);
+            if let Some(status) = conditional::evaluate(&headers, Some(&etag), None).status() {
+                return response
+                    .status(status)
+                    .body(Body::empty())
+                    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+            }
+            Ok(response
+                .header("content-type", "text/html; charset=utf-8")
+                .body(Body::from(full_html))
                 .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?)
+        } else {
+            serve_asset(&file_path, file, &headers)
         }
     } else {
         let template = TemplateAsset::get("404.html").expect("Template not found");
@@ -93,12 +246,224 @@ async fn serve_file(Path(full_path): Path<String>) -> Result<Response, StatusCod
     }
 }
 
-#[tokio::main]
-async fn main() {
-    let app = Router::new()
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+fn main() {
+    let config = match ServerConfig::load() {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            println!("{}", ConfigError::Help);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
+    };
+
+    // Fork while the process is still single-threaded.
+    #[cfg(unix)]
+    let daemon::Started {
+        pid_file,
+        mut startup,
+    } = match daemon::start(&config.daemon) {
+        Ok(started) => started,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(workers) = config.workers {
+        runtime.worker_threads(workers);
+    }
+    let result = runtime
+        .enable_all()
+        .build()
+        .expect("failed to build runtime")
+        .block_on(serve(config, || {
+            #[cfg(unix)]
+            if let Some(startup) = startup.take() {
+                startup.succeeded();
+            }
+        }));
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        // `exit` skips destructors, so the PID file is removed here first,
+        // before a waiting parent is told and exits too.
+        #[cfg(unix)]
+        {
+            drop(pid_file);
+            if let Some(startup) = startup {
+                startup.failed(&err);
+            }
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Runs the server until shut down. `ready` is called once the listeners are
+/// bound and privileges dropped, when startup can no longer fail.
+async fn serve(config: ServerConfig, ready: impl FnOnce()) -> Result<(), String> {
+    let metrics = Arc::new(Metrics::default());
+    let health = Arc::new(Health::default());
+    let mut app = Router::new()
         .route("/", get(home))
-        .route("/{*full_path}", get(serve_file));
+        .route("/{*full_path}", get(serve_file))
+        .route_layer(compress::compression())
+        .route_layer(middleware::from_fn(compress::weaken_etag));
+    if let TrailingSlash::Redirect = config.trailing_slash {
+        app = app.route_layer(middleware::from_fn(trailing_slash::redirect_trailing_slash));
+    }
+    if let Some(max_age) = config.cache_max_age {
+        let value = HeaderValue::try_from(format!("public, max-age={}", max_age.as_secs()))
+            .expect("cache-control value is always valid");
+        app = app.route_layer(middleware::from_fn_with_state(
+            value,
+            cache_control::set_cache_control,
+        ));
+    }
+    if let Some(path) = &config.metrics_path {
+        app = app.route(
+            path,
+            get(metrics::serve_metrics).with_state(metrics.clone()),
+        );
+    }
+    if config.health_checks {
+        app = app.route("/healthz", get(health::serve_liveness)).route(
+            "/readyz",
+            get(health::serve_readiness).with_state(health.clone()),
+        );
+    }
+    if let Some(limit) = config.handler_timeout {
+        app = app.layer(middleware::from_fn_with_state(
+            limit,
+            handler_timeout::limit_handler_time,
+        ));
+    }
+    app = app
+        .layer(middleware::from_fn(framing::reject_ambiguous_framing))
+        .layer(middleware::from_fn_with_state(
+            config.max_uri_length,
+            uri_limit::limit_uri_length,
+        ));
+    if let Some(rate) = config.rate_limit {
+        app = app.layer(middleware::from_fn_with_state(
+            Arc::new(RateLimiter::new(rate)),
+            rate_limit::limit_requests,
+        ));
+    }
+    let app = app
+        .layer(middleware::from_fn_with_state(
+            metrics.clone(),
+            metrics::record_requests,
+        ))
+        .layer(middleware::from_fn(access_log::log_requests))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(TrustedProxies {
+                nets: config.trusted_proxies,
+                header: config.forwarded_header,
+            }),
+            client_ip::resolve_client_ip,
+        ))
+        .layer(middleware::from_fn(request_id::propagate_request_id));
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // Sockets handed over by systemd replace the configured addresses.
+    #[cfg(unix)]
+    let mut listeners = ServerListener::from_systemd(config.socket)
+        .map_err(|err| format!("failed to use systemd sockets: {}", err))?;
+    #[cfg(not(unix))]
+    let mut listeners = Vec::new();
+    if listeners.is_empty() {
+        for addr in &config.listen {
+            let listener = ServerListener::bind(addr, config.socket, config.unix_mode)
+                .map_err(|err| format!("failed to bind {}: {}", addr, err))?;
+            listeners.push(listener);
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(credentials) = config.daemon.credentials
+        && let Err(err) = credentials.apply()
+    {
+        return Err(format!("failed to drop privileges: {}", err));
+    }
+    ready();
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        // Fail readiness first and keep serving for a while, so load
+        // balancers notice and stop sending traffic before we stop accepting.
+        health.set_draining();
+        tokio::time::sleep(config.shutdown_delay).await;
+        let _ = shutdown_tx.send(true);
+    });
+
+    let permits = Arc::new(Semaphore::new(
+        config.max_connections.unwrap_or(Semaphore::MAX_PERMITS),
+    ));
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let listener =
+            LimitListener::new(listener, permits.clone(), config.when_full, metrics.clone());
+        let listener = CountingListener::new(listener, metrics.clone());
+        let listener = TimeoutListener::new(
+            listener,
+            config.read_timeout,
+            config.write_timeout,
+            config.header_timeout,
+        );
+        let mut graceful_rx = shutdown_rx.clone();
+        let server = axum::serve(
+            listener,
+            app.clone()
+                .into_make_service_with_connect_info::<RemoteAddr>(),
+        )
+        .with_graceful_shutdown(async move {
+            let _ = graceful_rx.wait_for(|stop| *stop).await;
+        });
+        servers.spawn(server.into_future());
+    }
+    let all_servers = async move {
+        while let Some(result) = servers.join_next().await {
+            result.unwrap().unwrap();
+        }
+    };
+
+    // Stop waiting on lingering connections once the drain timeout has passed.
+    let mut drain_rx = shutdown_rx;
+    let drain_deadline = async move {
+        let _ = drain_rx.wait_for(|stop| *stop).await;
+        tokio::time::sleep(config.drain_timeout).await;
+    };
+
+    tokio::select! {
+        _ = all_servers => {},
+        _ = drain_deadline => {},
+    }
+    Ok(())
 }
@@ -0,0 +1,291 @@
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    serve::Listener,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(&'static str, u16), u64>>,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_micros: AtomicU64,
+    connections_open: AtomicU64,
+    connections_total: AtomicU64,
+    connections_rejected: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl Metrics {
+    fn observe_request(&self, method: &Method, status: u16, seconds: f64) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((method_label(method), status))
+            .or_default() += 1;
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_micros
+            .fetch_add((seconds * 1_000_000.0) as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_rejected_connection(&self) {
+        self.connections_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP cerial_http_requests_total Requests handled, by method and status.\n");
+        out.push_str("# TYPE cerial_http_requests_total counter\n");
+        for ((method, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "cerial_http_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+                method, status, count
+            );
+        }
+
+        out.push_str(
+            "# HELP cerial_http_request_duration_seconds Time spent producing a response.\n",
+        );
+        out.push_str("# TYPE cerial_http_request_duration_seconds histogram\n");
+        for (bucket, bound) in self.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "cerial_http_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "cerial_http_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let _ = writeln!(
+            out,
+            "cerial_http_request_duration_seconds_sum {}",
+            self.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(out, "cerial_http_request_duration_seconds_count {}", count);
+
+        for (name, kind, help, value) in [
+            (
+                "cerial_connections_open",
+                "gauge",
+                "Connections currently open.",
+                &self.connections_open,
+            ),
+            (
+                "cerial_connections_total",
+                "counter",
+                "Connections accepted.",
+                &self.connections_total,
+            ),
+            (
+                "cerial_connections_rejected_total",
+                "counter",
+                "Connections turned away because the connection limit was reached.",
+                &self.connections_rejected,
+            ),
+            (
+                "cerial_bytes_read_total",
+                "counter",
+                "Bytes read from clients.",
+                &self.bytes_read,
+            ),
+            (
+                "cerial_bytes_written_total",
+                "counter",
+                "Bytes written to clients.",
+                &self.bytes_written,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        out
+    }
+}
+
+pub async fn record_requests(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let response = next.run(request).await;
+    metrics.observe_request(
+        &method,
+        response.status().as_u16(),
+        start.elapsed().as_secs_f64(),
+    );
+    response
+}
+
+/// Clients choose the method, so anything outside the standard set is counted
+/// under one label instead of minting a new series per made-up method.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::CONNECT => "CONNECT",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::PATCH => "PATCH",
+        _ => "OTHER",
+    }
+}
+
+pub async fn serve_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [("content-type", "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+/// Counts open connections and the bytes moved over them.
+pub struct CountingListener<L> {
+    inner: L,
+    metrics: Arc<Metrics>,
+}
+
+impl<L> CountingListener<L> {
+    pub fn new(inner: L, metrics: Arc<Metrics>) -> Self {
+        CountingListener { inner, metrics }
+    }
+}
+
+impl<L: Listener> Listener for CountingListener<L> {
+    type Io = CountingIo<L::Io>;
+    type Addr = L::Addr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let (io, addr) = self.inner.accept().await;
+        self.metrics
+            .connections_open
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .connections_total
+            .fetch_add(1, Ordering::Relaxed);
+        (
+            CountingIo {
+                inner: io,
+                metrics: self.metrics.clone(),
+            },
+            addr,
+        )
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+pub struct CountingIo<T> {
+    inner: T,
+    metrics: Arc<Metrics>,
+}
+
+impl<T> Drop for CountingIo<T> {
+    fn drop(&mut self) {
+        self.metrics
+            .connections_open
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for CountingIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        this.metrics
+            .bytes_read
+            .fetch_add(read as u64, Ordering::Relaxed);
+        poll
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for CountingIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.metrics
+                .bytes_written
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.metrics
+                .bytes_written
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
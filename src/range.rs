@@ -0,0 +1,212 @@
+use std::{
+    ops::Range,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Requests asking for more ranges than this are served in full rather than
+// letting a client make us build a huge multipart body out of tiny slices.
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// The header was malformed or unsupported and should be ignored.
+    Ignore,
+    /// None of the ranges overlap the representation.
+    Unsatisfiable,
+    Satisfiable(Vec<Range<usize>>),
+}
+
+/// Parses a `Range` header value against a representation of `len` bytes.
+pub fn parse_range(header: &str, len: usize) -> RangeRequest {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Ignore;
+    };
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return RangeRequest::Ignore;
+        };
+        let range = if first.is_empty() {
+            let Ok(suffix) = last.parse::<usize>() else {
+                return RangeRequest::Ignore;
+            };
+            // An empty representation has no last byte for a suffix to end at.
+            (suffix > 0 && len > 0).then(|| len.saturating_sub(suffix)..len)
+        } else {
+            let Ok(start) = first.parse::<usize>() else {
+                return RangeRequest::Ignore;
+            };
+            let end = if last.is_empty() {
+                len
+            } else {
+                match last.parse::<usize>() {
+                    Ok(last) if last >= start => last.saturating_add(1).min(len),
+                    _ => return RangeRequest::Ignore,
+                }
+            };
+            (start < len).then_some(start..end)
+        };
+        ranges.extend(range);
+    }
+
+    if ranges.len() > MAX_RANGES {
+        RangeRequest::Ignore
+    } else if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Satisfiable(coalesce(ranges))
+    }
+}
+
+/// Merges overlapping and adjacent ranges, as RFC 9110 section 14.2 allows, so
+/// asking for the same bytes many times over cannot multiply the response.
+fn coalesce(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+pub fn content_range(range: &Range<usize>, len: usize) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+pub fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("cerial-{:x}", nanos)
+}
+
+/// Builds a `multipart/byteranges` body for several ranges of `data`.
+pub fn multipart_body(
+    data: &[u8],
+    ranges: &[Range<usize>],
+    content_type: &str,
+    boundary: &str,
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    for range in ranges {
+        body.extend_from_slice(
+            format!(
+                "\r\n--{}\r\ncontent-type: {}\r\ncontent-range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                content_range(range, data.len())
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&data[range.clone()]);
+    }
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+#[cfg(test)]
+// One-element vectors of ranges are exactly what single-range requests yield.
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-4", 10),
+            RangeRequest::Satisfiable(vec![0..5])
+        );
+        assert_eq!(
+            parse_range("bytes=5-", 10),
+            RangeRequest::Satisfiable(vec![5..10])
+        );
+        assert_eq!(
+            parse_range("bytes=-3", 10),
+            RangeRequest::Satisfiable(vec![7..10])
+        );
+    }
+
+    #[test]
+    fn clamps_ranges_to_the_length() {
+        assert_eq!(
+            parse_range("bytes=8-20", 10),
+            RangeRequest::Satisfiable(vec![8..10])
+        );
+        assert_eq!(
+            parse_range("bytes=-20", 10),
+            RangeRequest::Satisfiable(vec![0..10])
+        );
+    }
+
+    #[test]
+    fn parses_multiple_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-1, 4-5,-1", 10),
+            RangeRequest::Satisfiable(vec![0..2, 4..6, 9..10])
+        );
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        let header = format!("bytes={}", vec!["0-"; MAX_RANGES].join(","));
+        assert_eq!(
+            parse_range(&header, 10),
+            RangeRequest::Satisfiable(vec![0..10])
+        );
+        assert_eq!(
+            parse_range("bytes=0-4,2-6", 10),
+            RangeRequest::Satisfiable(vec![0..7])
+        );
+        assert_eq!(
+            parse_range("bytes=5-9,0-4", 10),
+            RangeRequest::Satisfiable(vec![0..10])
+        );
+        assert_eq!(
+            parse_range("bytes=2-3,-8", 10),
+            RangeRequest::Satisfiable(vec![2..10])
+        );
+    }
+
+    #[test]
+    fn sorts_disjoint_ranges() {
+        assert_eq!(
+            parse_range("bytes=6-7,0-1", 10),
+            RangeRequest::Satisfiable(vec![0..2, 6..8])
+        );
+    }
+
+    #[test]
+    fn drops_ranges_past_the_end() {
+        assert_eq!(
+            parse_range("bytes=0-1,10-", 10),
+            RangeRequest::Satisfiable(vec![0..2])
+        );
+        assert_eq!(parse_range("bytes=10-", 10), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 10), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn empty_representation_is_unsatisfiable() {
+        assert_eq!(parse_range("bytes=-5", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        assert_eq!(parse_range("items=0-1", 10), RangeRequest::Ignore);
+        assert_eq!(parse_range("bytes=1", 10), RangeRequest::Ignore);
+        assert_eq!(parse_range("bytes=a-b", 10), RangeRequest::Ignore);
+        assert_eq!(parse_range("bytes=5-2", 10), RangeRequest::Ignore);
+    }
+
+    #[test]
+    fn ignores_too_many_ranges() {
+        let header = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        assert_eq!(parse_range(&header, 10), RangeRequest::Ignore);
+    }
+}
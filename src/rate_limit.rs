@@ -0,0 +1,126 @@
+use crate::client_ip::ClientIp;
+use axum::{
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// How often idle buckets are swept out of the table.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A `requests/seconds` budget such as `100/60`.
+#[derive(Clone, Copy, Debug)]
+pub struct Rate {
+    requests: u32,
+    per: Duration,
+}
+
+impl FromStr for Rate {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (requests, secs) = s.split_once('/').ok_or(())?;
+        let requests: u32 = requests.trim().parse().map_err(|_| ())?;
+        let secs: u64 = secs.trim().parse().map_err(|_| ())?;
+        if requests == 0 || secs == 0 {
+            return Err(());
+        }
+        Ok(Rate {
+            requests,
+            per: Duration::from_secs(secs),
+        })
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets keyed by client IP. Each client may burst up to the full
+/// budget, which then refills continuously over the configured window.
+///
+/// Requests with no client IP, which come over a Unix socket without a
+/// forwarding header, all share the bucket keyed by `None`.
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    state: Mutex<(HashMap<Option<IpAddr>, Bucket>, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: Rate) -> Self {
+        let capacity = f64::from(rate.requests);
+        RateLimiter {
+            capacity,
+            refill_per_sec: capacity / rate.per.as_secs_f64(),
+            state: Mutex::new((HashMap::new(), Instant::now())),
+        }
+    }
+
+    /// Takes a token for `ip`, or returns how long until one is available.
+    fn acquire(&self, ip: Option<IpAddr>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let (buckets, last_sweep) = &mut *state;
+
+        // A bucket that has had time to refill completely carries no state
+        // worth keeping, so dropping it bounds the table by recent clients.
+        if now.duration_since(*last_sweep) >= SWEEP_INTERVAL {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.capacity);
+            *last_sweep = now;
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let tokens = self.refill(bucket, now);
+        bucket.updated = now;
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            Ok(())
+        } else {
+            bucket.tokens = tokens;
+            Err(Duration::from_secs_f64(
+                (1.0 - tokens) / self.refill_per_sec,
+            ))
+        }
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity)
+    }
+}
+
+/// Answers 429 Too Many Requests with `Retry-After` once a client has spent
+/// its budget.
+pub async fn limit_requests(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|&ClientIp(ip)| ip);
+    if let Err(retry_after) = limiter.acquire(ip) {
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, secs.to_string())],
+            "Too Many Requests",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
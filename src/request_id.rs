@@ -0,0 +1,69 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// Upstream IDs longer than this are replaced rather than echoed back.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Identifier of the current request, available to handlers as an extension.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub HeaderValue);
+
+/// Reuses the `X-Request-Id` sent by an upstream proxy, or generates one, and
+/// echoes it on the response.
+pub async fn propagate_request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .filter(|value| is_valid(value))
+        .cloned()
+        .unwrap_or_else(generate);
+    request.extensions_mut().insert(RequestId(id.clone()));
+
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(X_REQUEST_ID.clone(), id);
+    response
+}
+
+fn is_valid(value: &HeaderValue) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value.as_bytes().iter().all(|b| b.is_ascii_graphic())
+}
+
+/// Generates a random version 4 UUID.
+fn generate() -> HeaderValue {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    // RandomState keys are seeded randomly per thread and change on every
+    // construction; the counter keeps IDs distinct across threads regardless.
+    let mut bytes = [0u8; 16];
+    for half in bytes.chunks_mut(8) {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        half.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let uuid = format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    );
+    HeaderValue::from_str(&uuid).unwrap()
+}
@@ -0,0 +1,354 @@
+use axum::serve::Listener;
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Instant, Sleep, sleep},
+};
+
+/// Wraps accepted connections so that a read or write which makes no progress
+/// within its timeout fails with `TimedOut`, which makes hyper drop the
+/// connection. The read timeout also bounds how long an idle keep-alive
+/// connection is held open.
+///
+/// The header timeout bounds the request head as a whole, so a client cannot
+/// hold a connection by trickling in a byte just before each read times out.
+pub struct TimeoutListener<L> {
+    inner: L,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    header_timeout: Duration,
+}
+
+impl<L> TimeoutListener<L> {
+    pub fn new(
+        inner: L,
+        read_timeout: Duration,
+        write_timeout: Duration,
+        header_timeout: Duration,
+    ) -> Self {
+        TimeoutListener {
+            inner,
+            read_timeout,
+            write_timeout,
+            header_timeout,
+        }
+    }
+}
+
+impl<L: Listener> Listener for TimeoutListener<L> {
+    type Io = TimeoutIo<L::Io>;
+    type Addr = L::Addr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let (io, addr) = self.inner.accept().await;
+        (
+            TimeoutIo::new(
+                io,
+                self.read_timeout,
+                self.write_timeout,
+                self.header_timeout,
+            ),
+            addr,
+        )
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+pub struct TimeoutIo<T> {
+    inner: T,
+    read: Deadline,
+    write: Deadline,
+    head: HeadDeadline,
+}
+
+impl<T> TimeoutIo<T> {
+    fn new(
+        inner: T,
+        read_timeout: Duration,
+        write_timeout: Duration,
+        header_timeout: Duration,
+    ) -> Self {
+        TimeoutIo {
+            inner,
+            read: Deadline::new(read_timeout),
+            write: Deadline::new(write_timeout),
+            head: HeadDeadline::new(header_timeout),
+        }
+    }
+}
+
+struct Deadline {
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+    armed: bool,
+}
+
+impl Deadline {
+    fn new(timeout: Duration) -> Self {
+        Deadline {
+            timeout,
+            sleep: Box::pin(sleep(timeout)),
+            armed: false,
+        }
+    }
+
+    /// Polls the wrapped operation, starting the clock on the first pending
+    /// poll and stopping it once the operation completes.
+    fn poll<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        op: impl FnOnce(&mut Context<'_>) -> Poll<io::Result<R>>,
+    ) -> Poll<io::Result<R>> {
+        if !self.armed {
+            self.sleep.as_mut().reset(Instant::now() + self.timeout);
+            self.armed = true;
+        }
+        match op(cx) {
+            Poll::Ready(result) => {
+                self.armed = false;
+                Poll::Ready(result)
+            }
+            Poll::Pending => match self.sleep.as_mut().poll(cx) {
+                Poll::Ready(()) => {
+                    self.armed = false;
+                    Poll::Ready(Err(io::ErrorKind::TimedOut.into()))
+                }
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+/// Limits the time from the first byte of a request to the blank line ending
+/// its head. The clock starts on the first read after the connection opens or
+/// a response is written, and stops at the end of the head, so neither idle
+/// keep-alive time nor the body and handler count against it.
+struct HeadDeadline {
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+    state: HeadState,
+    // Whether the last byte other than CR was LF, so a second LF ends the head.
+    newline: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HeadState {
+    Idle,
+    Reading,
+    Done,
+}
+
+impl HeadDeadline {
+    fn new(timeout: Duration) -> Self {
+        HeadDeadline {
+            timeout,
+            sleep: Box::pin(sleep(timeout)),
+            state: HeadState::Idle,
+            newline: false,
+        }
+    }
+
+    fn expired(&mut self, cx: &mut Context<'_>) -> bool {
+        self.state == HeadState::Reading && self.sleep.as_mut().poll(cx).is_ready()
+    }
+
+    /// Feeds freshly read bytes through, starting the clock on the first of a
+    /// request and stopping it at the end of the head.
+    fn read(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            match self.state {
+                HeadState::Done => return,
+                HeadState::Idle => {
+                    self.sleep.as_mut().reset(Instant::now() + self.timeout);
+                    self.state = HeadState::Reading;
+                    self.newline = false;
+                }
+                HeadState::Reading => {}
+            }
+            if byte == b'\n' {
+                if self.newline {
+                    self.state = HeadState::Done;
+                }
+                self.newline = true;
+            } else if byte != b'\r' {
+                self.newline = false;
+            }
+        }
+    }
+
+    /// A response is being written, so the next bytes read start a new
+    /// request.
+    fn wrote(&mut self) {
+        self.state = HeadState::Idle;
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for TimeoutIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.head.expired(cx) {
+            return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
+        }
+        let inner = &mut this.inner;
+        let filled = buf.filled().len();
+        let result = this
+            .read
+            .poll(cx, |cx| Pin::new(&mut *inner).poll_read(cx, buf));
+        if let Poll::Ready(Ok(())) = result {
+            this.head.read(&buf.filled()[filled..]);
+        }
+        // Polled again so a stalled head also registers its deadline.
+        if this.head.expired(cx) {
+            return Poll::Ready(Err(io::ErrorKind::TimedOut.into()));
+        }
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for TimeoutIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.head.wrote();
+        let inner = &mut this.inner;
+        this.write
+            .poll(cx, |cx| Pin::new(inner).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        this.write.poll(cx, |cx| Pin::new(inner).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.head.wrote();
+        let inner = &mut this.inner;
+        this.write
+            .poll(cx, |cx| Pin::new(inner).poll_write_vectored(cx, bufs))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Waker;
+
+    // Creating the deadline's `Sleep` needs a runtime, hence `tokio::test`.
+    fn head() -> HeadDeadline {
+        HeadDeadline::new(Duration::from_secs(10))
+    }
+
+    #[tokio::test]
+    async fn first_bytes_start_the_clock() {
+        let mut head = head();
+        head.read(b"");
+        assert_eq!(head.state, HeadState::Idle);
+        head.read(b"GET / HTTP/1.1\r\n");
+        assert_eq!(head.state, HeadState::Reading);
+    }
+
+    #[tokio::test]
+    async fn blank_line_ends_the_head() {
+        let mut head = head();
+        head.read(b"GET / HTTP/1.1\r\nHost: x\r\n");
+        assert_eq!(head.state, HeadState::Reading);
+        head.read(b"\r\n");
+        assert_eq!(head.state, HeadState::Done);
+    }
+
+    #[tokio::test]
+    async fn blank_line_split_across_reads() {
+        let mut head = head();
+        head.read(b"GET / HTTP/1.1\r");
+        for byte in [b"\n", b"\r"] {
+            head.read(byte);
+            assert_eq!(head.state, HeadState::Reading);
+        }
+        head.read(b"\n");
+        assert_eq!(head.state, HeadState::Done);
+    }
+
+    #[tokio::test]
+    async fn bare_lf_line_endings() {
+        let mut head = head();
+        head.read(b"GET / HTTP/1.1\nHost: x\n");
+        assert_eq!(head.state, HeadState::Reading);
+        head.read(b"\n");
+        assert_eq!(head.state, HeadState::Done);
+    }
+
+    #[tokio::test]
+    async fn mixed_line_endings() {
+        let mut head = head();
+        head.read(b"GET / HTTP/1.1\r\n\n");
+        assert_eq!(head.state, HeadState::Done);
+    }
+
+    #[tokio::test]
+    async fn header_lines_do_not_end_the_head() {
+        let mut head = head();
+        head.read(b"GET / HTTP/1.1\r\nA: \r\r\nB: c\r\n");
+        assert_eq!(head.state, HeadState::Reading);
+    }
+
+    #[tokio::test]
+    async fn body_and_pipelined_bytes_wait_for_the_response() {
+        let mut head = head();
+        head.read(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n");
+        assert_eq!(head.state, HeadState::Done);
+        head.read(b"more");
+        assert_eq!(head.state, HeadState::Done);
+
+        head.wrote();
+        assert_eq!(head.state, HeadState::Idle);
+        // The line ending that closed the last head does not carry over.
+        head.read(b"\n");
+        assert_eq!(head.state, HeadState::Reading);
+        head.read(b"GET /c HTTP/1.1\r\n\r\n");
+        assert_eq!(head.state, HeadState::Done);
+    }
+
+    #[tokio::test]
+    async fn expires_only_while_reading() {
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut head = HeadDeadline::new(Duration::from_millis(1));
+        sleep(Duration::from_millis(5)).await;
+        assert!(!head.expired(&mut cx));
+
+        head.read(b"GET / HTTP/1.1\r\n");
+        sleep(Duration::from_millis(5)).await;
+        assert!(head.expired(&mut cx));
+
+        head.read(b"\r\n");
+        assert!(!head.expired(&mut cx));
+    }
+}
@@ -0,0 +1,55 @@
+use axum::{
+    extract::Request,
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::str::FromStr;
+
+/// How to treat a request path that ends in `/`.
+#[derive(Clone, Copy, Debug)]
+pub enum TrailingSlash {
+    /// Serve `/cat/` exactly as `/cat`.
+    Ignore,
+    /// Answer 308 Permanent Redirect to the path without the slash, so each
+    /// page has one URL.
+    Redirect,
+}
+
+impl FromStr for TrailingSlash {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(TrailingSlash::Ignore),
+            "redirect" => Ok(TrailingSlash::Redirect),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Redirects `/cat/` to `/cat`, keeping the query string. The location is
+/// rebuilt from the normalized path, so empty, `.` and `..` segments are
+/// resolved and `//example.com/` cannot turn into an off-site redirect.
+/// Browsers read `\` as `/` too, so it is percent-encoded for the same reason.
+pub async fn redirect_trailing_slash(request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    if path == "/" || !path.ends_with('/') {
+        return next.run(request).await;
+    }
+    // A path climbing above the root is left for the handler to reject.
+    let Some(path) = crate::normalize_path(path) else {
+        return next.run(request).await;
+    };
+
+    let mut location = format!("/{}", path.replace('\\', "%5C"));
+    if let Some(query) = request.uri().query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    (
+        StatusCode::PERMANENT_REDIRECT,
+        [(header::LOCATION, location)],
+    )
+        .into_response()
+}
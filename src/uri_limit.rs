@@ -0,0 +1,22 @@
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Answers 414 URI Too Long when the request target exceeds `max_len` bytes.
+pub async fn limit_uri_length(
+    State(max_len): State<usize>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let len = request
+        .uri()
+        .path_and_query()
+        .map_or(0, |path| path.as_str().len());
+    if len > max_len {
+        return (StatusCode::URI_TOO_LONG, "URI Too Long").into_response();
+    }
+    next.run(request).await
+}